@@ -3,23 +3,60 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! Metrics produced by the sled-agent for collection by oximeter.
+//!
+//! NOTE: the OTLP export path below (`spawn_otlp_exporter` and friends) pulls
+//! in `opentelemetry`, `opentelemetry_otlp`, and `opentelemetry_sdk`, none of
+//! which are sled-agent dependencies yet. Before this merges, add them to
+//! this crate's `Cargo.toml` pinned to a version whose `Resource::new`,
+//! `ResourceMetrics`/`ScopeMetrics` struct shapes, and `PushMetricExporter`
+//! trait match the usage here (this was written against the 0.27 API
+//! surface of `opentelemetry_sdk`/`opentelemetry-otlp`).
 
+use oximeter::types::Datum;
 use oximeter::types::MetricsError;
 use oximeter::types::ProducerRegistry;
+use oximeter::types::Sample;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::MetricExporter;
+use opentelemetry_sdk::metrics::data::Gauge;
+use opentelemetry_sdk::metrics::data::GaugeDataPoint;
+use opentelemetry_sdk::metrics::data::Histogram;
+use opentelemetry_sdk::metrics::data::HistogramDataPoint;
+use opentelemetry_sdk::metrics::data::Metric;
+use opentelemetry_sdk::metrics::data::MetricData;
+use opentelemetry_sdk::metrics::data::ResourceMetrics;
+use opentelemetry_sdk::metrics::data::ScopeMetrics;
+use opentelemetry_sdk::metrics::data::Sum;
+use opentelemetry_sdk::metrics::data::SumDataPoint;
+use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use opentelemetry_sdk::metrics::Temporality;
+use opentelemetry_sdk::Resource;
 use sled_hardware::Baseboard;
 use slog::Logger;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use uuid::Uuid;
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "illumos")] {
+        use anyhow::anyhow;
+        // NOTE: `kstat` is not yet a dependency of this crate; add it (and
+        // pin it) to this crate's `Cargo.toml` before this builds on
+        // illumos.
+        use kstat::Ctl as KstatCtl;
+        use oximeter::Producer;
         use oximeter_instruments::kstat::link;
         use oximeter_instruments::kstat::CollectionDetails;
         use oximeter_instruments::kstat::Error as KstatError;
         use oximeter_instruments::kstat::KstatSampler;
+        use oximeter_instruments::kstat::Target as KstatTarget;
         use oximeter_instruments::kstat::TargetId;
-        use std::collections::BTreeMap;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::atomic::Ordering;
         use std::sync::Mutex;
     } else {
         use anyhow::anyhow;
@@ -54,6 +91,17 @@ pub enum Error {
 
     #[error("Missing NULL byte in hostname")]
     HostnameMissingNull,
+
+    #[error("Failed to construct or run the OTLP exporter")]
+    Otlp(#[source] anyhow::Error),
+
+    #[cfg(target_os = "illumos")]
+    #[error("Failed to read raw kstat counter")]
+    RawKstat(#[source] anyhow::Error),
+
+    #[cfg(target_os = "illumos")]
+    #[error("Link `{0}` is already being histogram-sampled")]
+    AlreadyTracked(String),
 }
 
 // Basic metadata about the sled agent used when publishing metrics.
@@ -82,20 +130,159 @@ pub struct MetricsManager {
     _log: Logger,
     #[cfg(target_os = "illumos")]
     kstat_sampler: KstatSampler,
-    // TODO-scalability: We may want to generalize this to store any kind of
-    // tracked target, and use a naming scheme that allows us pick out which
-    // target we're interested in from the arguments.
-    //
-    // For example, we can use the link name to do this, for any physical or
-    // virtual link, because they need to be unique. We could also do the same
-    // for disks or memory. If we wanted to guarantee uniqueness, we could
-    // namespace them internally, e.g., `"datalink:{link_name}"` would be the
-    // real key.
+    // Tracks every kstat-based target we're currently sampling, regardless of
+    // kind. Keys are namespaced by `TrackedTargetKind`, e.g.
+    // `"datalink:{name}"` or `"vnic:{name}"`, which keeps them unique across
+    // kinds without needing a separate `Arc<Mutex<BTreeMap>>` field (and a new
+    // pair of track/stop methods) every time we start collecting a new class
+    // of kstat.
     #[cfg(target_os = "illumos")]
-    tracked_links: Arc<Mutex<BTreeMap<String, TargetId>>>,
+    tracked_targets: Arc<Mutex<BTreeMap<String, TargetId>>>,
+    // Tracks the background work sampling each link's raw `obytes64` kstat
+    // for `track_physical_link_histogram`, keyed by link name, so a second
+    // call for the same link is rejected rather than registering a
+    // duplicate producer, and so tracking can be stopped later.
+    #[cfg(target_os = "illumos")]
+    histogram_tasks: Arc<Mutex<BTreeMap<String, HistogramSamplingTask>>>,
     registry: ProducerRegistry,
 }
 
+/// Handles for the background work started by `track_physical_link_histogram`
+/// for a single link.
+#[cfg(target_os = "illumos")]
+#[derive(Debug)]
+struct HistogramSamplingTask {
+    // Tells the dedicated OS thread doing blocking kstat reads to stop.
+    stop: Arc<AtomicBool>,
+    // The async task that resets the histogram on its own fixed cadence.
+    reset_task: tokio::task::JoinHandle<()>,
+}
+
+/// The kind of target tracked in `MetricsManager::tracked_targets`.
+///
+/// This is used only to namespace the keys of that map, so that targets of
+/// different kinds can't collide even if they happen to share a name.
+#[cfg(target_os = "illumos")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum TrackedTargetKind {
+    Datalink,
+    VirtualDatalink,
+    Vnic,
+}
+
+#[cfg(target_os = "illumos")]
+impl TrackedTargetKind {
+    fn namespace(&self) -> &'static str {
+        match self {
+            TrackedTargetKind::Datalink => "datalink",
+            TrackedTargetKind::VirtualDatalink => "vdatalink",
+            TrackedTargetKind::Vnic => "vnic",
+        }
+    }
+
+    fn key(&self, name: impl AsRef<str>) -> String {
+        format!("{}:{}", self.namespace(), name.as_ref())
+    }
+}
+
+/// Parameters controlling how a `SparseHistogram` buckets the values it's
+/// given.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(not(target_os = "illumos"), allow(dead_code))]
+pub struct HistogramAggregationConfig {
+    /// The logarithm base used to space buckets.
+    pub log_base: f64,
+    /// The number of buckets per power of `log_base`.
+    pub buckets_per_magnitude: u32,
+}
+
+impl Default for HistogramAggregationConfig {
+    fn default() -> Self {
+        Self { log_base: 2.0, buckets_per_magnitude: 8 }
+    }
+}
+
+/// Parameters controlling `MetricsManager::spawn_statsd_sink`.
+#[derive(Clone, Copy, Debug)]
+pub struct StatsdSinkConfig {
+    /// The maximum number of bytes to pack into a single UDP datagram.
+    pub mtu: usize,
+}
+
+impl Default for StatsdSinkConfig {
+    fn default() -> Self {
+        // Fits within the Ethernet MTU after accounting for IP and UDP
+        // headers; the same default DogStatsD clients commonly use.
+        Self { mtu: 1432 }
+    }
+}
+
+/// A sparse, log-spaced functional histogram, modeled on Glean's functional
+/// histograms.
+///
+/// Rather than sampling and forwarding every raw value we see, this
+/// accumulates counts into buckets whose lower bound is
+/// `floor(log_base.powf(exponent / buckets_per_magnitude))` for the largest
+/// integer `exponent` whose unfloored bound does not exceed the value. Only
+/// buckets that have actually seen a value are stored, so the structure needs
+/// no precomputed range and is mergeable across instances by summing counts
+/// per `bucket_min`.
+#[derive(Clone, Debug)]
+#[cfg_attr(not(target_os = "illumos"), allow(dead_code))]
+struct SparseHistogram {
+    config: HistogramAggregationConfig,
+    // Maps each bucket's lower bound to the number of values recorded in it.
+    // Non-positive values are recorded under the dedicated `0` bucket, since
+    // they have no logarithm.
+    buckets: std::collections::BTreeMap<u64, u64>,
+}
+
+#[cfg_attr(not(target_os = "illumos"), allow(dead_code))]
+impl SparseHistogram {
+    fn new(config: HistogramAggregationConfig) -> Self {
+        Self { config, buckets: std::collections::BTreeMap::new() }
+    }
+
+    /// Record one occurrence of `value`.
+    fn record(&mut self, value: i64) {
+        let bucket_min = if value <= 0 {
+            0
+        } else {
+            let exponent = f64::from(self.config.buckets_per_magnitude)
+                * (value as f64).ln()
+                / self.config.log_base.ln();
+            // A huge exponent would overflow the `powf` below into `inf`;
+            // saturate it instead of producing a garbage bucket.
+            let exponent = exponent.floor().clamp(0.0, u32::MAX as f64);
+            let magnitude = exponent / f64::from(self.config.buckets_per_magnitude);
+            self.config.log_base.powf(magnitude).floor() as u64
+        };
+        *self.buckets.entry(bucket_min).or_insert(0) += 1;
+    }
+
+    /// Return a copy of the current bucket counts, without disturbing them.
+    ///
+    /// Reading never resets this histogram -- it may be collected by any
+    /// number of independent exporters (oximeter's own poll, our OTLP task,
+    /// a Prometheus scrape, a statsd flush), and none of them should be able
+    /// to make the others see an empty histogram just by reading first.
+    /// Resetting is a separate, deliberate operation on its own cadence; see
+    /// `reset`.
+    fn snapshot(&self) -> std::collections::BTreeMap<u64, u64> {
+        self.buckets.clone()
+    }
+
+    /// Clear the accumulated bucket counts.
+    ///
+    /// Called once per `METRIC_COLLECTION_INTERVAL` by the task that owns
+    /// this histogram, so each interval's snapshots reflect only the deltas
+    /// accumulated since the last reset -- independent of how many times, or
+    /// by whom, it was read in between.
+    fn reset(&mut self) {
+        self.buckets.clear();
+    }
+}
+
 impl MetricsManager {
     /// Construct a new metrics manager.
     ///
@@ -115,7 +302,8 @@ impl MetricsManager {
                 registry
                     .register_producer(kstat_sampler.clone())
                     .map_err(Error::Registry)?;
-                let tracked_links = Arc::new(Mutex::new(BTreeMap::new()));
+                let tracked_targets = Arc::new(Mutex::new(BTreeMap::new()));
+                let histogram_tasks = Arc::new(Mutex::new(BTreeMap::new()));
             }
         }
         Ok(Self {
@@ -124,7 +312,9 @@ impl MetricsManager {
             #[cfg(target_os = "illumos")]
             kstat_sampler,
             #[cfg(target_os = "illumos")]
-            tracked_links,
+            tracked_targets,
+            #[cfg(target_os = "illumos")]
+            histogram_tasks,
             registry,
         })
     }
@@ -133,6 +323,775 @@ impl MetricsManager {
     pub fn registry(&self) -> &ProducerRegistry {
         &self.registry
     }
+
+    /// Return the set of currently-tracked target keys, for introspection.
+    #[allow(dead_code)]
+    pub fn tracked_targets(&self) -> BTreeSet<String> {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "illumos")] {
+                self.tracked_targets.lock().unwrap().keys().cloned().collect()
+            } else {
+                BTreeSet::new()
+            }
+        }
+    }
+
+    // Return the serial number out of the baseboard, if one exists.
+    fn serial_number(&self) -> String {
+        match &self.metadata.baseboard {
+            Baseboard::Gimlet { identifier, .. } => identifier.clone(),
+            Baseboard::Unknown => String::from("unknown"),
+            Baseboard::Pc { identifier, .. } => identifier.clone(),
+        }
+    }
+
+    // Build the OTLP resource attributes shared by every metric we export:
+    // the identifiers we otherwise attach to each oximeter target.
+    fn resource_attributes(&self) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new("sled_id", self.metadata.sled_id.to_string()),
+            KeyValue::new("rack_id", self.metadata.rack_id.to_string()),
+            KeyValue::new("serial", self.serial_number()),
+            KeyValue::new(
+                "hostname",
+                hostname().unwrap_or_else(|_| String::from("unknown")),
+            ),
+        ]
+    }
+
+    /// Spawn a background task that periodically drains the contained
+    /// `ProducerRegistry` and pushes the resulting samples to an OTLP/gRPC
+    /// collector, as an alternative (or complement) to being polled by
+    /// `oximeter` directly.
+    pub fn spawn_otlp_exporter(
+        &self,
+        endpoint: impl AsRef<str>,
+        export_interval: Duration,
+    ) -> Result<tokio::task::JoinHandle<()>, Error> {
+        let exporter = MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint.as_ref())
+            .with_temporality(Temporality::Cumulative)
+            .build()
+            .map_err(|e| Error::Otlp(anyhow::Error::new(e)))?;
+        let resource = Resource::new(self.resource_attributes());
+        let registry = self.registry.clone();
+        let log = self._log.clone();
+        Ok(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(export_interval);
+            loop {
+                ticker.tick().await;
+                let mut resource_metrics =
+                    match collect_resource_metrics(&registry, &resource) {
+                        Ok(m) => m,
+                        Err(e) => {
+                            slog::warn!(
+                                log,
+                                "failed to collect samples for OTLP export";
+                                "error" => ?e,
+                            );
+                            continue;
+                        }
+                    };
+                if let Err(e) = exporter.export(&mut resource_metrics).await {
+                    slog::warn!(
+                        log,
+                        "failed to export metrics via OTLP";
+                        "error" => ?e,
+                    );
+                }
+            }
+        }))
+    }
+
+    /// Render all current samples in Prometheus text exposition format.
+    ///
+    /// This walks the contained `ProducerRegistry`, collects the current
+    /// value of every sample, and renders `# HELP`/`# TYPE` lines followed by
+    /// the sample lines `write_prometheus_sample` produces for each sample
+    /// (one for a counter or gauge, several for a histogram), with a label
+    /// set built from the sample's target
+    /// and metric fields (e.g. `sled_id`, `rack_id`, `serial`, `hostname`,
+    /// `link_name`). This can be wired into a `/metrics` route so that sites
+    /// with existing Prometheus infrastructure can scrape a sled directly,
+    /// without standing up `oximeter`.
+    pub fn prometheus_text(&self) -> Result<String, Error> {
+        let samples = self.registry.collect().map_err(Error::Registry)?;
+        let mut out = String::new();
+        let mut described = BTreeSet::new();
+        for sample in &samples {
+            let name = prometheus_metric_name(sample);
+            if described.insert(name.clone()) {
+                let _ = writeln!(
+                    out,
+                    "# HELP {name} Samples of the `{}` oximeter timeseries.",
+                    sample.timeseries_name(),
+                );
+                let _ = writeln!(
+                    out,
+                    "# TYPE {name} {}",
+                    prometheus_metric_type(sample.measurement().datum()),
+                );
+            }
+            write_prometheus_sample(&mut out, &name, sample);
+        }
+        Ok(out)
+    }
+
+    /// Spawn a background task that periodically drains the contained
+    /// `ProducerRegistry` and fire-and-forgets the samples to `addr` as
+    /// DogStatsD-formatted UDP packets.
+    ///
+    /// Cumulative datums are reported as `|c` deltas against the last time we
+    /// saw them, `I64`/`F64` gauges as `|g`, and `f64` oximeter histograms as
+    /// one `|h` sample (valued at the bucket's lower edge) per sample added to
+    /// each bucket since the previous flush; a counter or histogram produces
+    /// nothing the first time it's seen. Tags are derived from each sample's
+    /// target and metric fields, e.g.
+    /// `#sled_id:...,rack_id:...,link_name:...`. Metrics are batched into
+    /// datagrams up to `config.mtu` bytes; if the socket would block, the
+    /// pending batch is dropped (and a warning logged) rather than stalling
+    /// the exporter.
+    pub fn spawn_statsd_sink(
+        &self,
+        addr: SocketAddr,
+        flush_interval: Duration,
+        config: StatsdSinkConfig,
+    ) -> tokio::task::JoinHandle<()> {
+        let registry = self.registry.clone();
+        let log = self._log.clone();
+        tokio::spawn(async move {
+            let socket = match tokio::net::UdpSocket::bind("0.0.0.0:0").await
+            {
+                Ok(socket) => socket,
+                Err(e) => {
+                    slog::error!(
+                        log,
+                        "failed to bind statsd socket";
+                        "error" => ?e,
+                    );
+                    return;
+                }
+            };
+            if let Err(e) = socket.connect(addr).await {
+                slog::error!(
+                    log,
+                    "failed to connect statsd socket";
+                    "error" => ?e,
+                    "addr" => %addr,
+                );
+                return;
+            }
+            let mut previous_cumulatives = BTreeMap::new();
+            let mut previous_histograms = BTreeMap::new();
+            let mut ticker = tokio::time::interval(flush_interval);
+            loop {
+                ticker.tick().await;
+                let samples = match registry.collect() {
+                    Ok(samples) => samples,
+                    Err(e) => {
+                        slog::warn!(
+                            log,
+                            "failed to collect samples for statsd export";
+                            "error" => ?e,
+                        );
+                        continue;
+                    }
+                };
+                let live_cumulative_keys: BTreeSet<String> =
+                    samples.iter().filter_map(statsd_cumulative_key).collect();
+                previous_cumulatives
+                    .retain(|key, _| live_cumulative_keys.contains(key));
+                let live_histogram_keys: BTreeSet<String> =
+                    samples.iter().filter_map(statsd_histogram_key).collect();
+                previous_histograms
+                    .retain(|key, _| live_histogram_keys.contains(key));
+                let lines: Vec<String> = samples
+                    .iter()
+                    .flat_map(|sample| {
+                        sample_to_statsd_lines(
+                            sample,
+                            &mut previous_cumulatives,
+                            &mut previous_histograms,
+                        )
+                    })
+                    .collect();
+                for batch in batch_statsd_lines(&lines, config.mtu) {
+                    if let Err(e) = socket.try_send(batch.as_bytes()) {
+                        slog::warn!(
+                            log,
+                            "dropping statsd batch";
+                            "error" => ?e,
+                            "size" => batch.len(),
+                        );
+                    }
+                }
+            }
+        })
+    }
+}
+
+// Drain every producer registered with `registry` and convert their samples
+// into a single OTLP `ResourceMetrics`, ready to hand to an exporter.
+fn collect_resource_metrics(
+    registry: &ProducerRegistry,
+    resource: &Resource,
+) -> Result<ResourceMetrics, Error> {
+    let samples = registry.collect().map_err(Error::Registry)?;
+    let metrics = samples.into_iter().filter_map(sample_to_metric).collect();
+    Ok(ResourceMetrics {
+        resource: resource.clone(),
+        scope_metrics: vec![ScopeMetrics {
+            scope: Default::default(),
+            metrics,
+        }],
+    })
+}
+
+// Map a single oximeter `Sample` into an OTLP `Metric`, if its datum is one we
+// know how to represent. Cumulative counters become an OTLP `Sum` with
+// `AggregationTemporality::Cumulative`, `I64` and `F64` gauges become an OTLP
+// `Gauge`, and `f64` oximeter histograms become an OTLP `Histogram`. Every
+// other datum kind is dropped: booleans, strings and byte blobs, but also
+// gauges of other numeric types and histograms of other types.
+fn sample_to_metric(sample: Sample) -> Option<Metric> {
+    let attributes: Vec<KeyValue> = sample
+        .fields()
+        .map(|(name, value)| KeyValue::new(name.to_string(), value.to_string()))
+        .collect();
+    let measurement = sample.measurement();
+    let time = measurement.timestamp();
+    let start_time = measurement.start_time().unwrap_or(time);
+    let data = match measurement.datum() {
+        Datum::CumulativeI64(c) => MetricData::Sum(Sum {
+            data_points: vec![SumDataPoint {
+                attributes,
+                start_time,
+                time,
+                value: c.value(),
+                exemplars: Vec::new(),
+            }],
+            temporality: Temporality::Cumulative,
+            is_monotonic: true,
+        }),
+        Datum::CumulativeU64(c) => MetricData::Sum(Sum {
+            data_points: vec![SumDataPoint {
+                attributes,
+                start_time,
+                time,
+                value: c.value() as i64,
+                exemplars: Vec::new(),
+            }],
+            temporality: Temporality::Cumulative,
+            is_monotonic: true,
+        }),
+        Datum::CumulativeF32(c) => MetricData::Sum(Sum {
+            data_points: vec![SumDataPoint {
+                attributes,
+                start_time,
+                time,
+                value: c.value() as f64,
+                exemplars: Vec::new(),
+            }],
+            temporality: Temporality::Cumulative,
+            is_monotonic: true,
+        }),
+        Datum::CumulativeF64(c) => MetricData::Sum(Sum {
+            data_points: vec![SumDataPoint {
+                attributes,
+                start_time,
+                time,
+                value: c.value(),
+                exemplars: Vec::new(),
+            }],
+            temporality: Temporality::Cumulative,
+            is_monotonic: true,
+        }),
+        // Always report gauges as f64, even for integer-valued datums, so a
+        // single gauge arm can't silently truncate a fractional value.
+        Datum::I64(v) => MetricData::Gauge(Gauge {
+            data_points: vec![GaugeDataPoint {
+                attributes,
+                start_time: Some(start_time),
+                time,
+                value: *v as f64,
+                exemplars: Vec::new(),
+            }],
+        }),
+        Datum::F64(v) => MetricData::Gauge(Gauge {
+            data_points: vec![GaugeDataPoint {
+                attributes,
+                start_time: Some(start_time),
+                time,
+                value: *v,
+                exemplars: Vec::new(),
+            }],
+        }),
+        Datum::HistogramF64(h) => {
+            let bins = h.bins();
+            // OTLP wants `bucket_counts.len() == bounds.len() + 1`, with
+            // `bounds` the upper edges *between* buckets. oximeter's bins
+            // already carry one count per bucket, but its outermost edges
+            // are +/-inf rather than a finite boundary, so only the
+            // boundaries strictly between two buckets become an OTLP bound;
+            // every bucket (including the two unbounded outer ones) keeps
+            // its count.
+            let bounds = bins[..bins.len().saturating_sub(1)]
+                .iter()
+                .map(|bin| bin.range.end())
+                .collect();
+            let bucket_counts = bins.iter().map(|bin| bin.count).collect();
+            MetricData::Histogram(Histogram {
+                data_points: vec![HistogramDataPoint {
+                    attributes,
+                    start_time,
+                    time,
+                    count: h.n_samples(),
+                    bounds,
+                    bucket_counts,
+                    sum: h.sum(),
+                    min: h.min(),
+                    max: h.max(),
+                    exemplars: Vec::new(),
+                }],
+                temporality: Temporality::Cumulative,
+            })
+        }
+        _ => return None,
+    };
+    Some(Metric {
+        name: sample.timeseries_name().to_string(),
+        description: String::new(),
+        unit: String::new(),
+        data,
+    })
+}
+
+// Prometheus metric names may only contain `[a-zA-Z0-9_:]`; oximeter
+// timeseries names are colon-separated, so just swap out everything else.
+fn prometheus_metric_name(sample: &Sample) -> String {
+    sample
+        .timeseries_name()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == ':' { c } else { '_' })
+        .collect()
+}
+
+// Return the Prometheus `# TYPE` for a datum: cumulative counters map to
+// `counter`, `f64` oximeter histograms map to `histogram`, and everything else
+// (including plain gauges and histograms of other types) maps to `gauge`.
+fn prometheus_metric_type(datum: &Datum) -> &'static str {
+    match datum {
+        Datum::CumulativeI64(_)
+        | Datum::CumulativeU64(_)
+        | Datum::CumulativeF32(_)
+        | Datum::CumulativeF64(_) => "counter",
+        Datum::HistogramF64(_) => "histogram",
+        _ => "gauge",
+    }
+}
+
+// Render `sample`'s target and metric fields as a Prometheus label set, e.g.
+// `{sled_id="...",link_name="..."}`, optionally appending `extra` labels such
+// as `le` for histogram buckets.
+fn prometheus_labels(sample: &Sample, extra: &[(&str, String)]) -> String {
+    let pairs: Vec<String> = sample
+        .fields()
+        .map(|(name, value)| {
+            format!(
+                "{name}=\"{}\"",
+                value.to_string().replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .chain(extra.iter().map(|(name, value)| {
+            format!(
+                "{name}=\"{}\"",
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        }))
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+// Render a histogram bucket's upper bound the way Prometheus expects:
+// `+Inf`/`-Inf` for the unbounded outer buckets, rather than Rust's `inf`.
+fn prometheus_bound(bound: f64) -> String {
+    if bound == f64::INFINITY {
+        "+Inf".to_string()
+    } else if bound == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        bound.to_string()
+    }
+}
+
+// Append one or more Prometheus sample lines for `sample` to `out`.
+//
+// Cumulative datums map to a single `counter` line, `I64` and `F64` gauges to
+// a single `gauge` line, and `f64` oximeter histograms to the usual
+// `_bucket`/`_sum`/`_count` trio, with cumulative bucket counts as Prometheus
+// expects. Every other datum kind (including gauges of other numeric types)
+// produces no lines.
+fn write_prometheus_sample(out: &mut String, name: &str, sample: &Sample) {
+    let labels = prometheus_labels(sample, &[]);
+    match sample.measurement().datum() {
+        Datum::CumulativeI64(c) => {
+            let _ = writeln!(out, "{name}{labels} {}", c.value());
+        }
+        Datum::CumulativeU64(c) => {
+            let _ = writeln!(out, "{name}{labels} {}", c.value());
+        }
+        Datum::CumulativeF32(c) => {
+            let _ = writeln!(out, "{name}{labels} {}", c.value());
+        }
+        Datum::CumulativeF64(c) => {
+            let _ = writeln!(out, "{name}{labels} {}", c.value());
+        }
+        Datum::I64(v) => {
+            let _ = writeln!(out, "{name}{labels} {v}");
+        }
+        Datum::F64(v) => {
+            let _ = writeln!(out, "{name}{labels} {v}");
+        }
+        Datum::HistogramF64(h) => {
+            // Prometheus requires every histogram to have a `le="+Inf"`
+            // bucket whose count equals `_count`; oximeter's last bin
+            // already covers that range (its upper edge is `f64::INFINITY`),
+            // so as long as we render non-finite bounds as `+Inf`/`-Inf`
+            // instead of Rust's `inf`, the last bucket we emit already is
+            // that `+Inf` bucket.
+            let mut cumulative_count = 0u64;
+            for bin in h.bins() {
+                cumulative_count += bin.count;
+                let bucket_labels = prometheus_labels(
+                    sample,
+                    &[("le", prometheus_bound(bin.range.end()))],
+                );
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{bucket_labels} {cumulative_count}",
+                );
+            }
+            let _ = writeln!(out, "{name}_sum{labels} {}", h.sum());
+            let _ = writeln!(out, "{name}_count{labels} {}", h.n_samples());
+        }
+        _ => {}
+    }
+}
+
+// The key state tracked per-timeseries under `previous_cumulatives` or
+// `previous_histograms` is filed under: the sample's (post-namespacing) name
+// plus its rendered tag set, which together identify one timeseries.
+fn statsd_timeseries_key(sample: &Sample) -> String {
+    format!(
+        "{}|{}",
+        sample.timeseries_name().replace(':', "."),
+        statsd_tags(sample),
+    )
+}
+
+fn statsd_cumulative_key(sample: &Sample) -> Option<String> {
+    matches!(
+        sample.measurement().datum(),
+        Datum::CumulativeI64(_)
+            | Datum::CumulativeU64(_)
+            | Datum::CumulativeF32(_)
+            | Datum::CumulativeF64(_)
+    )
+    .then(|| statsd_timeseries_key(sample))
+}
+
+fn statsd_histogram_key(sample: &Sample) -> Option<String> {
+    matches!(sample.measurement().datum(), Datum::HistogramF64(_))
+        .then(|| statsd_timeseries_key(sample))
+}
+
+// Convert one oximeter `Sample` into its DogStatsD line(s). Cumulative
+// datums become a single `|c` line carrying the delta since the last time we
+// saw that same name and tag set (and no line at all the first time, since
+// there's no prior value to take a delta against); `I64` and `F64` gauges
+// become a single `|g` line; `f64` oximeter histograms are likewise
+// cumulative over the process lifetime, so they become `|h` lines only for
+// the *increase* in each bucket's count since the last collection (and no
+// lines at all the first time a histogram is seen). Every other datum kind,
+// including gauges and histograms of other types, produces no lines.
+fn sample_to_statsd_lines(
+    sample: &Sample,
+    previous_cumulatives: &mut BTreeMap<String, f64>,
+    previous_histograms: &mut BTreeMap<String, BTreeMap<u64, u64>>,
+) -> Vec<String> {
+    let name = sample.timeseries_name().replace(':', ".");
+    let tags = statsd_tags(sample);
+    match sample.measurement().datum() {
+        Datum::CumulativeI64(c) => statsd_counter_line(
+            &name,
+            &tags,
+            c.value() as f64,
+            previous_cumulatives,
+        )
+        .into_iter()
+        .collect(),
+        Datum::CumulativeU64(c) => statsd_counter_line(
+            &name,
+            &tags,
+            c.value() as f64,
+            previous_cumulatives,
+        )
+        .into_iter()
+        .collect(),
+        Datum::CumulativeF32(c) => statsd_counter_line(
+            &name,
+            &tags,
+            c.value() as f64,
+            previous_cumulatives,
+        )
+        .into_iter()
+        .collect(),
+        Datum::CumulativeF64(c) => statsd_counter_line(
+            &name,
+            &tags,
+            c.value(),
+            previous_cumulatives,
+        )
+        .into_iter()
+        .collect(),
+        Datum::I64(v) => vec![statsd_line(&name, &tags, *v as f64, "g")],
+        Datum::F64(v) => vec![statsd_line(&name, &tags, *v, "g")],
+        Datum::HistogramF64(h) => {
+            statsd_histogram_lines(&name, &tags, h, previous_histograms)
+        }
+        _ => Vec::new(),
+    }
+}
+
+// Emit one `|h` line per sample added to each bucket since the last
+// collection. oximeter's histogram datum reports the full lifetime bucket
+// counts every time, so without diffing against the previous collection a
+// long-lived, high-traffic timeseries would re-send its entire history on
+// every flush and the downstream agent would double-count it forever.
+fn statsd_histogram_lines(
+    name: &str,
+    tags: &str,
+    histogram: &oximeter::histogram::Histogram<f64>,
+    previous_histograms: &mut BTreeMap<String, BTreeMap<u64, u64>>,
+) -> Vec<String> {
+    let key = format!("{name}|{tags}");
+    let current: BTreeMap<u64, u64> = histogram
+        .bins()
+        .iter()
+        .map(|bin| (bin.range.start().to_bits(), bin.count))
+        .collect();
+    // The very first time we see a given name and tag set there's no prior
+    // bucket counts to diff against, so -- same as `statsd_counter_line` --
+    // we record a baseline and emit nothing, rather than reporting this
+    // histogram's entire lifetime history as a single interval's worth of
+    // samples.
+    let Some(previous) = previous_histograms.insert(key, current.clone())
+    else {
+        return Vec::new();
+    };
+    let mut lines = Vec::new();
+    for (bucket_key, count) in &current {
+        let previous_count = previous.get(bucket_key).copied().unwrap_or(0);
+        let delta = count.saturating_sub(previous_count);
+        let value = f64::from_bits(*bucket_key);
+        for _ in 0..delta {
+            lines.push(statsd_line(name, tags, value, "h"));
+        }
+    }
+    lines
+}
+
+// Compute the delta of a cumulative counter against the last value we saw
+// for the same name and tag set, and format it as a DogStatsD `|c` line. The
+// very first time a given name and tag set is observed there's no prior
+// value to diff against, so nothing is emitted (otherwise we'd report a
+// counter's entire lifetime total as a single interval's delta).
+fn statsd_counter_line(
+    name: &str,
+    tags: &str,
+    value: f64,
+    previous_cumulatives: &mut BTreeMap<String, f64>,
+) -> Option<String> {
+    let key = format!("{name}|{tags}");
+    let previous = previous_cumulatives.insert(key, value);
+    let delta = (value - previous?).max(0.0);
+    Some(statsd_line(name, tags, delta, "c"))
+}
+
+// Render `sample`'s target and metric fields as a comma-separated DogStatsD
+// tag list, e.g. `sled_id:...,rack_id:...,link_name:...`, escaping any
+// characters DogStatsD treats as delimiters (`,`, `|`, and newlines) so a
+// field value can't corrupt the line's structure.
+fn statsd_tags(sample: &Sample) -> String {
+    sample
+        .fields()
+        .map(|(name, value)| {
+            format!("{name}:{}", escape_statsd_tag_value(&value.to_string()))
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape_statsd_tag_value(value: &str) -> String {
+    value.replace(['\n', '\r'], " ").replace(',', "_").replace('|', "_")
+}
+
+fn statsd_line(name: &str, tags: &str, value: f64, kind: &str) -> String {
+    if tags.is_empty() {
+        format!("{name}:{value}|{kind}")
+    } else {
+        format!("{name}:{value}|{kind}|#{tags}")
+    }
+}
+
+// Greedily pack `lines` into newline-joined batches, each no larger than
+// `mtu` bytes, so several metrics can share a single UDP datagram.
+// A single line longer than `mtu` is still emitted as its own batch, on the
+// theory that an oversized datagram (likely dropped or truncated by the
+// network) is preferable to silently discarding that metric.
+fn batch_statsd_lines(lines: &[String], mtu: usize) -> Vec<String> {
+    let mut batches = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        let needed = if current.is_empty() {
+            line.len()
+        } else {
+            current.len() + 1 + line.len()
+        };
+        if needed > mtu && !current.is_empty() {
+            batches.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    batches
+}
+
+/// An oximeter target identifying the datalink a throughput histogram was
+/// aggregated from.
+#[cfg(target_os = "illumos")]
+#[derive(Clone, Debug, oximeter::Target)]
+struct LinkThroughput {
+    rack_id: Uuid,
+    sled_id: Uuid,
+    serial: String,
+    hostname: String,
+    link_name: String,
+}
+
+/// An oximeter metric carrying a client-side aggregated histogram of
+/// per-interval byte-count deltas.
+#[cfg(target_os = "illumos")]
+#[derive(Clone, Debug, oximeter::Metric)]
+struct BytesPerInterval {
+    datum: oximeter::histogram::Histogram<f64>,
+}
+
+/// Accumulates per-interval deltas of a single raw, monotonic kstat counter
+/// into a `SparseHistogram`, and hands the result to `oximeter` as a single
+/// histogram sample each time it's collected.
+#[cfg(target_os = "illumos")]
+#[derive(Debug)]
+struct AggregatedCounterHistogram {
+    target: LinkThroughput,
+    histogram: SparseHistogram,
+    // The last raw counter value we observed, used to compute the next
+    // delta. `None` until the first observation.
+    previous: Option<u64>,
+}
+
+#[cfg(target_os = "illumos")]
+impl AggregatedCounterHistogram {
+    fn new(target: LinkThroughput, config: HistogramAggregationConfig) -> Self {
+        Self { target, histogram: SparseHistogram::new(config), previous: None }
+    }
+
+    /// Record one new raw (cumulative) counter value, converting it to a
+    /// delta against the last-observed value and recording that delta.
+    ///
+    /// The first observation only records a baseline: there's no prior value
+    /// to diff against, so treating `raw` itself as the delta would record
+    /// the counter's entire lifetime total as a single interval's worth of
+    /// traffic. A `raw` value lower than the last one we saw means the
+    /// underlying counter was reset (e.g., the link was replumbed); we treat
+    /// the whole of `raw` as the delta in that case, rather than
+    /// underflowing.
+    fn observe(&mut self, raw: u64) {
+        let delta = match self.previous {
+            None => None,
+            Some(previous) if raw >= previous => Some(raw - previous),
+            Some(_) => Some(raw),
+        };
+        self.previous = Some(raw);
+        if let Some(delta) = delta {
+            self.histogram.record(delta.min(i64::MAX as u64) as i64);
+        }
+    }
+
+    /// Reset the accumulated histogram, discarding everything recorded so
+    /// far.
+    ///
+    /// This is the only thing that clears `histogram`; it's called on our
+    /// own fixed cadence (see `track_physical_link_histogram`), never as a
+    /// side effect of `to_sample`, so reading this histogram through any
+    /// number of independent exporters never disturbs what the others see.
+    fn reset(&mut self) {
+        self.histogram.reset();
+    }
+
+    /// Render the current histogram into a single oximeter sample, without
+    /// disturbing its accumulated state.
+    ///
+    /// An idle link (or the first collection, before a second sampler tick
+    /// has given us anything to diff against) records no deltas in an
+    /// interval, leaving `buckets` empty. `Histogram::new` rejects an empty
+    /// set of bins, so in that case we fall back to a single placeholder bin
+    /// edge at `0.0` (whose two unbounded bins span everything), producing a
+    /// valid but empty histogram rather than failing the whole collection.
+    fn to_sample(&self) -> Result<Sample, MetricsError> {
+        let buckets = self.histogram.snapshot();
+        let bins: Vec<f64> = if buckets.is_empty() {
+            vec![0.0]
+        } else {
+            buckets.keys().map(|&b| b as f64).collect()
+        };
+        let mut histogram = oximeter::histogram::Histogram::new(&bins)?;
+        for (bucket_min, count) in buckets {
+            for _ in 0..count {
+                histogram.sample(bucket_min as f64)?;
+            }
+        }
+        let metric = BytesPerInterval { datum: histogram };
+        Sample::new(&self.target, &metric)
+    }
+}
+
+/// An oximeter `Producer` that reads an `AggregatedCounterHistogram` on
+/// every poll, without resetting it.
+#[cfg(target_os = "illumos")]
+#[derive(Clone)]
+struct LinkThroughputProducer {
+    inner: Arc<Mutex<AggregatedCounterHistogram>>,
+}
+
+#[cfg(target_os = "illumos")]
+impl Producer for LinkThroughputProducer {
+    fn produce(
+        &mut self,
+    ) -> Result<Box<dyn Iterator<Item = Sample> + 'static>, MetricsError> {
+        let sample = self.inner.lock().unwrap().to_sample()?;
+        Ok(Box::new(std::iter::once(sample)))
+    }
 }
 
 #[cfg(target_os = "illumos")]
@@ -152,33 +1111,27 @@ impl MetricsManager {
             link_name: link_name.as_ref().to_string(),
         };
         let details = CollectionDetails::never(interval);
-        let id = self
-            .kstat_sampler
-            .add_target(link, details)
-            .await
-            .map_err(Error::Kstat)?;
-        self.tracked_links
-            .lock()
-            .unwrap()
-            .insert(link_name.as_ref().to_string(), id);
-        Ok(())
+        self.add_target(
+            TrackedTargetKind::Datalink,
+            link_name.as_ref(),
+            link,
+            details,
+        )
+        .await
     }
 
-    /// Stop tracking metrics for a datalink.
+    /// Stop tracking metrics for a physical datalink.
     ///
-    /// This works for both physical and virtual links.
+    /// For a virtual datalink, use `stop_tracking_virtual_link` instead --
+    /// physical and virtual links are tracked under distinct
+    /// `TrackedTargetKind`s so a physical and virtual link that happen to
+    /// share a name can't collide and overwrite each other's `TargetId`.
     #[allow(dead_code)]
     pub async fn stop_tracking_link(
         &self,
         link_name: impl AsRef<str>,
     ) -> Result<(), Error> {
-        let maybe_id =
-            self.tracked_links.lock().unwrap().remove(link_name.as_ref());
-        if let Some(id) = maybe_id {
-            self.kstat_sampler.remove_target(id).await.map_err(Error::Kstat)
-        } else {
-            Ok(())
-        }
+        self.remove_target(TrackedTargetKind::Datalink, link_name).await
     }
 
     /// Track metrics for a virtual datalink.
@@ -197,19 +1150,189 @@ impl MetricsManager {
             link_name: link_name.as_ref().to_string(),
         };
         let details = CollectionDetails::never(interval);
-        self.kstat_sampler
-            .add_target(link, details)
+        self.add_target(
+            TrackedTargetKind::VirtualDatalink,
+            link_name.as_ref(),
+            link,
+            details,
+        )
+        .await
+    }
+
+    /// Stop tracking metrics for a virtual datalink previously tracked by
+    /// `track_virtual_link`.
+    #[allow(dead_code)]
+    pub async fn stop_tracking_virtual_link(
+        &self,
+        link_name: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        self.remove_target(TrackedTargetKind::VirtualDatalink, link_name)
             .await
-            .map(|_| ())
-            .map_err(Error::Kstat)
     }
 
-    // Return the serial number out of the baseboard, if one exists.
-    fn serial_number(&self) -> String {
-        match &self.metadata.baseboard {
-            Baseboard::Gimlet { identifier, .. } => identifier.clone(),
-            Baseboard::Unknown => String::from("unknown"),
-            Baseboard::Pc { identifier, .. } => identifier.clone(),
+    /// Track metrics for a guest instance's virtual NIC (viona).
+    ///
+    /// Held pending upstream support: this needs
+    /// `oximeter_instruments::kstat::link::GuestNetworkInterface` to exist
+    /// and actually implement `KstatTarget` against the viona instance named
+    /// by `minor` (the kstat-interest lookup that finds the `viona` kstat
+    /// chain entry for that minor number has to live in that upstream type).
+    /// Until that wiring lands in `oximeter-instruments`, tracking a guest
+    /// NIC here would either fail to compile or silently register a
+    /// producer that never finds any viona kstats to sample, so this returns
+    /// an error instead.
+    #[allow(unused_variables)]
+    pub async fn track_guest_nic(
+        &self,
+        nic_id: Uuid,
+        minor: u32,
+        interval: Duration,
+    ) -> Result<(), Error> {
+        Err(Error::Kstat(anyhow!(
+            "guest NIC kstat tracking is not yet supported: \
+             oximeter-instruments does not carry the viona kstat-interest \
+             wiring this depends on"
+        )))
+    }
+
+    /// Stop tracking metrics for a guest instance's virtual NIC.
+    ///
+    /// Always returns an error, since `track_guest_nic` never starts tracking
+    /// a guest NIC.
+    #[allow(dead_code, unused_variables)]
+    pub async fn stop_tracking_guest_nic(
+        &self,
+        nic_id: Uuid,
+    ) -> Result<(), Error> {
+        Err(Error::Kstat(anyhow!(
+            "guest NIC kstat tracking is not yet supported: \
+             oximeter-instruments does not carry the viona kstat-interest \
+             wiring this depends on"
+        )))
+    }
+
+    /// Track a client-side aggregated histogram of per-interval byte-count
+    /// deltas for a physical datalink.
+    ///
+    /// Sampling some kstats every few seconds produces large sample volumes;
+    /// rather than forward every raw delta to `oximeter`, this reads the
+    /// link's raw counter directly every `sample_interval`, accumulates the
+    /// deltas between reads into a sparse, log-spaced histogram (see
+    /// `SparseHistogram`), and registers a producer that hands that
+    /// histogram to `oximeter` as a single sample, once per
+    /// `METRIC_COLLECTION_INTERVAL`.
+    #[allow(dead_code)]
+    pub async fn track_physical_link_histogram(
+        &self,
+        link_name: impl AsRef<str>,
+        sample_interval: Duration,
+        config: HistogramAggregationConfig,
+    ) -> Result<(), Error> {
+        let link_name = link_name.as_ref().to_string();
+        let mut histogram_tasks = self.histogram_tasks.lock().unwrap();
+        if histogram_tasks.contains_key(&link_name) {
+            return Err(Error::AlreadyTracked(link_name));
+        }
+        let target = LinkThroughput {
+            rack_id: self.metadata.rack_id,
+            sled_id: self.metadata.sled_id,
+            serial: self.serial_number(),
+            hostname: hostname()?,
+            link_name: link_name.clone(),
+        };
+        let producer = LinkThroughputProducer {
+            inner: Arc::new(Mutex::new(AggregatedCounterHistogram::new(
+                target, config,
+            ))),
+        };
+        self.registry
+            .register_producer(producer.clone())
+            .map_err(Error::Registry)?;
+        // Build the kstat handle once up front, rather than walking the
+        // entire kstat chain again on every tick.
+        let mut ctl =
+            KstatCtl::new().map_err(|e| Error::RawKstat(anyhow!(e)))?;
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // `ctl.lookup`/`ctl.read` are blocking FFI calls, so they run on a
+        // dedicated OS thread rather than inside an async task, where they'd
+        // block a tokio worker (and everything else scheduled on it) on
+        // every tick.
+        let thread_stop = Arc::clone(&stop);
+        let thread_producer = producer.clone();
+        let thread_link_name = link_name.clone();
+        std::thread::spawn(move || {
+            loop {
+                if let Ok(raw) = read_obytes64(&mut ctl, &thread_link_name) {
+                    thread_producer.inner.lock().unwrap().observe(raw);
+                }
+                std::thread::sleep(sample_interval);
+                if thread_stop.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+        });
+
+        // Resetting the histogram is cheap, so it's fine to do from a
+        // regular async task on its own fixed cadence, independent of how
+        // many times (or by whom) it's read via `produce` in between.
+        let reset_task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(METRIC_COLLECTION_INTERVAL);
+            loop {
+                ticker.tick().await;
+                producer.inner.lock().unwrap().reset();
+            }
+        });
+
+        histogram_tasks
+            .insert(link_name, HistogramSamplingTask { stop, reset_task });
+        Ok(())
+    }
+
+    /// Stop sampling the per-interval byte-count histogram previously
+    /// started by `track_physical_link_histogram` for `link_name`, if any.
+    #[allow(dead_code)]
+    pub fn stop_tracking_link_histogram(&self, link_name: impl AsRef<str>) {
+        if let Some(task) =
+            self.histogram_tasks.lock().unwrap().remove(link_name.as_ref())
+        {
+            task.stop.store(true, Ordering::Relaxed);
+            task.reset_task.abort();
+        }
+    }
+
+    /// Start tracking an arbitrary kstat-based target, recording it in
+    /// `tracked_targets` under its namespaced key so it can later be removed
+    /// by `kind` and `name` alone.
+    async fn add_target<T: KstatTarget>(
+        &self,
+        kind: TrackedTargetKind,
+        name: impl AsRef<str>,
+        target: T,
+        details: CollectionDetails,
+    ) -> Result<(), Error> {
+        let id = self
+            .kstat_sampler
+            .add_target(target, details)
+            .await
+            .map_err(Error::Kstat)?;
+        self.tracked_targets.lock().unwrap().insert(kind.key(name), id);
+        Ok(())
+    }
+
+    /// Stop tracking the target previously registered under `kind` and
+    /// `name`, if any.
+    async fn remove_target(
+        &self,
+        kind: TrackedTargetKind,
+        name: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let maybe_id =
+            self.tracked_targets.lock().unwrap().remove(&kind.key(name));
+        if let Some(id) = maybe_id {
+            self.kstat_sampler.remove_target(id).await.map_err(Error::Kstat)
+        } else {
+            Ok(())
         }
     }
 }
@@ -227,9 +1350,7 @@ impl MetricsManager {
         )))
     }
 
-    /// Stop tracking metrics for a datalink.
-    ///
-    /// This works for both physical and virtual links.
+    /// Stop tracking metrics for a physical datalink.
     #[allow(dead_code)]
     pub async fn stop_tracking_link(
         &self,
@@ -252,10 +1373,61 @@ impl MetricsManager {
             "kstat metrics are not supported on this platform"
         )))
     }
+
+    /// Stop tracking metrics for a virtual datalink.
+    #[allow(dead_code)]
+    pub async fn stop_tracking_virtual_link(
+        &self,
+        _link_name: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        Err(Error::Kstat(anyhow!(
+            "kstat metrics are not supported on this platform"
+        )))
+    }
+
+    /// Track metrics for a guest instance's virtual NIC (viona).
+    pub async fn track_guest_nic(
+        &self,
+        _nic_id: Uuid,
+        _minor: u32,
+        _interval: Duration,
+    ) -> Result<(), Error> {
+        Err(Error::Kstat(anyhow!(
+            "kstat metrics are not supported on this platform"
+        )))
+    }
+
+    /// Stop tracking metrics for a guest instance's virtual NIC.
+    #[allow(dead_code)]
+    pub async fn stop_tracking_guest_nic(
+        &self,
+        _nic_id: Uuid,
+    ) -> Result<(), Error> {
+        Err(Error::Kstat(anyhow!(
+            "kstat metrics are not supported on this platform"
+        )))
+    }
+
+    /// Track a client-side aggregated histogram of per-interval byte-count
+    /// deltas for a physical datalink.
+    #[allow(dead_code)]
+    pub async fn track_physical_link_histogram(
+        &self,
+        _link_name: impl AsRef<str>,
+        _sample_interval: Duration,
+        _config: HistogramAggregationConfig,
+    ) -> Result<(), Error> {
+        Err(Error::Kstat(anyhow!(
+            "kstat metrics are not supported on this platform"
+        )))
+    }
+
+    /// Stop sampling the per-interval byte-count histogram for a link.
+    #[allow(dead_code)]
+    pub fn stop_tracking_link_histogram(&self, _link_name: impl AsRef<str>) {}
 }
 
 // Return the current hostname if possible.
-#[cfg(target_os = "illumos")]
 fn hostname() -> Result<String, Error> {
     // See netdb.h
     const MAX_LEN: usize = 256;
@@ -278,3 +1450,224 @@ fn hostname() -> Result<String, Error> {
         Err(std::io::Error::last_os_error()).map_err(|_| Error::NonUtf8Hostname)
     }
 }
+
+// Read the current value of a link's raw `obytes64` kstat, bypassing
+// `KstatSampler` entirely so we can compute our own per-interval deltas for
+// client-side histogram aggregation. Takes the `KstatCtl` handle by
+// reference rather than constructing one, since opening a handle walks the
+// entire kstat chain and callers sample on a tight interval.
+#[cfg(target_os = "illumos")]
+fn read_obytes64(ctl: &mut KstatCtl, link_name: &str) -> Result<u64, Error> {
+    let kstat = ctl
+        .lookup(Some("link"), None, Some(link_name))
+        .map_err(|e| Error::RawKstat(anyhow!(e)))?;
+    let data = ctl.read(&kstat).map_err(|e| Error::RawKstat(anyhow!(e)))?;
+    data.data
+        .iter()
+        .find_map(|named| {
+            (named.name == "obytes64").then(|| named.value.as_u64())
+        })
+        .flatten()
+        .ok_or_else(|| {
+            Error::RawKstat(anyhow!(
+                "no `obytes64` statistic on link `{}`",
+                link_name
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, oximeter::Target)]
+    struct TestTarget {
+        name: String,
+    }
+
+    #[derive(Clone, Debug, oximeter::Metric)]
+    struct TestGauge {
+        datum: i64,
+    }
+
+    #[derive(Clone, Debug, oximeter::Metric)]
+    struct TestCounter {
+        datum: oximeter::types::Cumulative<i64>,
+    }
+
+    #[derive(Clone, Debug, oximeter::Metric)]
+    struct TestHistogram {
+        datum: oximeter::histogram::Histogram<f64>,
+    }
+
+    fn test_target() -> TestTarget {
+        TestTarget { name: "widget".to_string() }
+    }
+
+    #[test]
+    fn sparse_histogram_snapshot_is_non_destructive() {
+        let mut histogram =
+            SparseHistogram::new(HistogramAggregationConfig::default());
+        histogram.record(5);
+        histogram.record(5);
+        histogram.record(100);
+        let total: u64 = histogram.snapshot().values().sum();
+        assert_eq!(total, 3);
+        // Reading the snapshot again shouldn't have disturbed anything.
+        let total_again: u64 = histogram.snapshot().values().sum();
+        assert_eq!(total_again, 3);
+    }
+
+    #[test]
+    fn sparse_histogram_reset_clears_buckets() {
+        let mut histogram =
+            SparseHistogram::new(HistogramAggregationConfig::default());
+        histogram.record(5);
+        histogram.reset();
+        assert!(histogram.snapshot().is_empty());
+    }
+
+    #[test]
+    fn sparse_histogram_non_positive_values_share_the_zero_bucket() {
+        let mut histogram =
+            SparseHistogram::new(HistogramAggregationConfig::default());
+        histogram.record(0);
+        histogram.record(-5);
+        let buckets = histogram.snapshot();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets.get(&0), Some(&2));
+    }
+
+    #[cfg(target_os = "illumos")]
+    #[test]
+    fn aggregated_counter_histogram_first_observation_sets_a_baseline() {
+        let target = LinkThroughput {
+            rack_id: Uuid::new_v4(),
+            sled_id: Uuid::new_v4(),
+            serial: "serial".to_string(),
+            hostname: "host".to_string(),
+            link_name: "link".to_string(),
+        };
+        let mut agg = AggregatedCounterHistogram::new(
+            target,
+            HistogramAggregationConfig::default(),
+        );
+        agg.observe(100);
+        assert!(agg.histogram.snapshot().is_empty());
+        agg.observe(150);
+        let total: u64 = agg.histogram.snapshot().values().sum();
+        assert_eq!(total, 1);
+        agg.reset();
+        assert!(agg.histogram.snapshot().is_empty());
+    }
+
+    #[test]
+    fn prometheus_bound_formats_infinities() {
+        assert_eq!(prometheus_bound(f64::INFINITY), "+Inf");
+        assert_eq!(prometheus_bound(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(prometheus_bound(3.5), "3.5");
+    }
+
+    #[test]
+    fn escape_statsd_tag_value_escapes_delimiters() {
+        assert_eq!(escape_statsd_tag_value("a,b|c\r\n"), "a_b_c  ");
+    }
+
+    #[test]
+    fn batch_statsd_lines_packs_until_the_mtu() {
+        let lines =
+            vec!["aaaa".to_string(), "bbbb".to_string(), "cccc".to_string()];
+        let batches = batch_statsd_lines(&lines, 9);
+        assert_eq!(
+            batches,
+            vec!["aaaa\nbbbb".to_string(), "cccc".to_string()]
+        );
+    }
+
+    #[test]
+    fn batch_statsd_lines_emits_an_oversized_line_alone() {
+        let lines = vec!["a".repeat(20)];
+        let batches = batch_statsd_lines(&lines, 10);
+        assert_eq!(batches, vec!["a".repeat(20)]);
+    }
+
+    #[test]
+    fn statsd_counter_line_skips_the_first_observation_then_diffs() {
+        let mut previous = BTreeMap::new();
+        assert_eq!(statsd_counter_line("m", "", 10.0, &mut previous), None);
+        assert_eq!(
+            statsd_counter_line("m", "", 15.0, &mut previous),
+            Some("m:5|c".to_string())
+        );
+    }
+
+    #[test]
+    fn statsd_histogram_lines_skips_the_first_observation_then_diffs() {
+        let bins = [1.0, 2.0, 4.0];
+        let mut histogram =
+            oximeter::histogram::Histogram::new(&bins).unwrap();
+        histogram.sample(1.0).unwrap();
+        let mut previous = BTreeMap::new();
+        assert!(
+            statsd_histogram_lines("m", "", &histogram, &mut previous)
+                .is_empty()
+        );
+
+        histogram.sample(1.0).unwrap();
+        histogram.sample(2.0).unwrap();
+        let lines =
+            statsd_histogram_lines("m", "", &histogram, &mut previous);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.contains(&"m:1|h".to_string()));
+        assert!(lines.contains(&"m:2|h".to_string()));
+    }
+
+    #[test]
+    fn write_prometheus_sample_renders_a_gauge() {
+        let metric = TestGauge { datum: 42 };
+        let sample = Sample::new(&test_target(), &metric).unwrap();
+        let mut out = String::new();
+        write_prometheus_sample(&mut out, "widget_count", &sample);
+        assert_eq!(out, "widget_count{name=\"widget\"} 42\n");
+    }
+
+    #[test]
+    fn sample_to_metric_maps_gauge_to_otlp_gauge() {
+        let metric = TestGauge { datum: 42 };
+        let sample = Sample::new(&test_target(), &metric).unwrap();
+        let otlp_metric =
+            sample_to_metric(sample).expect("a gauge datum is supported");
+        assert!(matches!(otlp_metric.data, MetricData::Gauge(_)));
+    }
+
+    #[test]
+    fn sample_to_metric_maps_cumulative_to_otlp_sum() {
+        let metric =
+            TestCounter { datum: oximeter::types::Cumulative::new(3) };
+        let sample = Sample::new(&test_target(), &metric).unwrap();
+        let otlp_metric = sample_to_metric(sample)
+            .expect("a cumulative datum is supported");
+        assert!(matches!(otlp_metric.data, MetricData::Sum(_)));
+    }
+
+    #[test]
+    fn sample_to_metric_histogram_bucket_counts_outnumber_bounds_by_one() {
+        let mut histogram =
+            oximeter::histogram::Histogram::new(&[1.0, 2.0]).unwrap();
+        histogram.sample(1.0).unwrap();
+        let metric = TestHistogram { datum: histogram };
+        let sample = Sample::new(&test_target(), &metric).unwrap();
+        let otlp_metric = sample_to_metric(sample)
+            .expect("a histogram datum is supported");
+        match otlp_metric.data {
+            MetricData::Histogram(h) => {
+                let data_point = &h.data_points[0];
+                assert_eq!(
+                    data_point.bucket_counts.len(),
+                    data_point.bounds.len() + 1
+                );
+            }
+            other => panic!("expected a histogram, got {other:?}"),
+        }
+    }
+}